@@ -1,10 +1,17 @@
 use std::cmp::Ordering;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::result;
+use std::time::Duration;
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
 use thiserror::Error;
 
+#[cfg(feature = "pool")]
+use r2d2::Pool;
+#[cfg(feature = "pool")]
+use r2d2_sqlite::SqliteConnectionManager;
+
 /// The App ID is a user-defined i32 value set in a SQLite database headers.
 /// We expect that if the `application_id` value is this one, it's a valid file
 /// created by (or compatible with) our app.
@@ -17,24 +24,42 @@ const SQLITE_APP_ID: i32 = 0x27011990;
 /// new minor+ release of the app.
 const SQLITE_USER_VERSION: u32 = 1;
 
-/// Returns the current database schema.
-fn schema() -> String {
-    format!(
+/// A single step in the schema migration chain.
+///
+/// Each migration brings the database from the version immediately below
+/// `target_version` to `target_version`. Migrations are applied in ascending
+/// order, each inside its own transaction, so a crash mid-upgrade leaves the
+/// file at a coherent, already-committed intermediate version.
+struct Migration {
+    /// The `user_version` the database is at once this migration has run.
+    target_version: u32,
+    /// Applies the migration's schema changes.
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// All migrations, ordered from the oldest to the current schema version.
+const MIGRATIONS: &[Migration] = &[Migration {
+    target_version: 1,
+    apply: migrate_to_v1,
+}];
+
+/// Creates the version 1 schema: sets the application id and the
+/// `app_metadata` table.
+///
+/// Idempotent: a file can reach this step with the schema already in place
+/// (e.g. `user_version` was reset to 0 on an already-initialized file), so
+/// this must be safe to re-run rather than fail with "table already exists".
+fn migrate_to_v1(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(&format!(
         "PRAGMA application_id = {};
 
-        CREATE TABLE app_metadata (
+        CREATE TABLE IF NOT EXISTS app_metadata (
             id INTEGER PRIMARY KEY,
             version TEXT NOT NULL,
             upgraded_from TEXT
-        );
-
-        INSERT INTO app_metadata (version, upgraded_from) VALUES ('{}', NULL);
-
-        PRAGMA user_version = {};",
-        SQLITE_APP_ID,
-        env!("CARGO_PKG_VERSION"),
-        SQLITE_USER_VERSION
-    )
+        );",
+        SQLITE_APP_ID
+    ))
 }
 
 /// Error type for errors returned by this module.
@@ -55,6 +80,20 @@ pub enum DbError {
 
     #[error("failed to read the file, its format is invalid")]
     InvalidFileError,
+
+    /// The file needs to go through the migration chain, but was opened
+    /// read-only, so it can't be upgraded.
+    #[error("the file needs to be upgraded, but was opened read-only")]
+    UpgradeRequired,
+
+    /// A running statement was aborted through an `InterruptHandle`.
+    #[error("the operation was interrupted")]
+    Interrupted,
+
+    /// Failed to check out a connection from the pool.
+    #[cfg(feature = "pool")]
+    #[error("failed to get a connection from the pool")]
+    PoolFailed { cause: r2d2::Error },
 }
 
 /// Specialized result type for the db module.
@@ -81,6 +120,7 @@ impl<T> IntoResult<T> for rusqlite::Result<T> {
 
     fn into_read_failed(self) -> Result<T> {
         match self {
+            Err(e) if is_interrupted(&e) => Err(DbError::Interrupted),
             Err(e) => Err(DbError::ReadFailed { cause: e }),
             Ok(r) => Ok(r),
         }
@@ -88,6 +128,7 @@ impl<T> IntoResult<T> for rusqlite::Result<T> {
 
     fn into_write_failed(self) -> Result<T> {
         match self {
+            Err(e) if is_interrupted(&e) => Err(DbError::Interrupted),
             Err(e) => Err(DbError::WriteFailed { cause: e }),
             Ok(r) => Ok(r),
         }
@@ -103,17 +144,337 @@ impl<T> IntoResult<T> for rusqlite::Result<T> {
     }
 }
 
+/// Returns whether a rusqlite error is SQLite reporting that the statement
+/// was aborted through `sqlite3_interrupt` (our `InterruptHandle`).
+fn is_interrupted(e: &rusqlite::Error) -> bool {
+    e.sqlite_error_code() == Some(rusqlite::ffi::ErrorCode::OperationInterrupted)
+}
+
+/// Checks that a freshly-opened connection is a valid app file (or a new,
+/// uninitialized one), and runs the migration chain on it if needed and
+/// allowed by `mode`.
+fn check_and_prepare(connection: &mut Connection, mode: OpenMode) -> Result<()> {
+    let application_id: i32 = connection
+        .query_row(
+            "SELECT application_id FROM pragma_application_id()",
+            [],
+            |r| r.get(0),
+        )
+        .into_invalid_or_read_failed()?;
+
+    if application_id != 0 && application_id != SQLITE_APP_ID {
+        return Err(DbError::InvalidFileError);
+    }
+
+    let user_version = read_user_version(connection)?;
+    if user_version < SQLITE_USER_VERSION {
+        match mode {
+            OpenMode::ReadWrite => run_migrations(connection, user_version)?,
+            OpenMode::ReadOnly => return Err(DbError::UpgradeRequired),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the `user_version` pragma of a connection.
+fn read_user_version(connection: &Connection) -> Result<u32> {
+    connection
+        .query_row("SELECT user_version FROM pragma_user_version", [], |r| {
+            r.get(0)
+        })
+        .into_read_failed()
+}
+
+/// Runs every migration whose `target_version` is in `(from_version,
+/// SQLITE_USER_VERSION]`, in ascending order, each inside its own
+/// transaction. `PRAGMA user_version` is bumped at the end of each step, so
+/// a crash mid-upgrade leaves the file at a coherent intermediate version.
+///
+/// Once the chain has run, records the upgrade in `app_metadata`, including
+/// the app version the file is upgraded from (if any).
+fn run_migrations(connection: &mut Connection, from_version: u32) -> Result<()> {
+    let upgraded_from: Option<String> = if from_version > 0 {
+        connection
+            .query_row(
+                "SELECT version FROM app_metadata ORDER BY id DESC LIMIT 1",
+                [],
+                |r| r.get(0),
+            )
+            .optional()
+            .into_read_failed()?
+    } else {
+        None
+    };
+
+    for migration in MIGRATIONS {
+        if migration.target_version <= from_version {
+            continue;
+        }
+
+        let transaction = connection.transaction().into_write_failed()?;
+        (migration.apply)(&transaction).into_write_failed()?;
+        transaction
+            .pragma_update(None, "user_version", migration.target_version)
+            .into_write_failed()?;
+        transaction.commit().into_write_failed()?;
+    }
+
+    let transaction = connection.transaction().into_write_failed()?;
+    transaction
+        .execute(
+            "INSERT INTO app_metadata (version, upgraded_from) VALUES (?1, ?2)",
+            rusqlite::params![env!("CARGO_PKG_VERSION"), upgraded_from],
+        )
+        .into_write_failed()?;
+    transaction.commit().into_write_failed()?;
+
+    Ok(())
+}
+
+/// Controls how `try_open_with` opens the underlying connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Open the file for reading and writing, creating it if it doesn't
+    /// exist. The file is initialized or upgraded as needed. This is what
+    /// `try_open` uses.
+    ReadWrite,
+    /// Open the file strictly for reading. The file is never created,
+    /// initialized or upgraded: if it needs an upgrade, `try_open_with`
+    /// fails with `DbError::UpgradeRequired` rather than operate on a stale
+    /// schema.
+    ReadOnly,
+}
+
+impl OpenMode {
+    /// Starts from `OpenFlags::default()` (which also carries
+    /// `SQLITE_OPEN_NO_MUTEX` and `SQLITE_OPEN_URI`) and only swaps the
+    /// read/write/create bits, so `ReadOnly` doesn't regress the threading
+    /// mode or URI parsing that the default, and `ReadWrite`, rely on.
+    fn flags(self) -> OpenFlags {
+        let base = OpenFlags::default()
+            - OpenFlags::SQLITE_OPEN_READ_WRITE
+            - OpenFlags::SQLITE_OPEN_CREATE
+            - OpenFlags::SQLITE_OPEN_READ_ONLY;
+
+        match self {
+            OpenMode::ReadWrite => {
+                base | OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+            }
+            OpenMode::ReadOnly => base | OpenFlags::SQLITE_OPEN_READ_ONLY,
+        }
+    }
+}
+
+/// `PRAGMA synchronous` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_pragma_value(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+/// A registered scalar-function callback, as stored by `AppFileOptions`.
+type ScalarFunctionRegistrar = Box<dyn Fn(&Connection) -> rusqlite::Result<()> + Send + Sync>;
+
+/// Builder for the connection-preparation phase that `try_open_with` runs
+/// right after opening a connection, but before the schema/version logic
+/// (and, outside of any transaction).
+///
+/// With the `pool` feature, the same preparation is re-applied to every
+/// physical connection the pool opens afterwards.
+pub struct AppFileOptions {
+    mode: OpenMode,
+    wal: bool,
+    foreign_keys: bool,
+    synchronous: Option<Synchronous>,
+    busy_timeout: Duration,
+    scalar_functions: Vec<ScalarFunctionRegistrar>,
+}
+
+impl fmt::Debug for AppFileOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppFileOptions")
+            .field("mode", &self.mode)
+            .field("wal", &self.wal)
+            .field("foreign_keys", &self.foreign_keys)
+            .field("synchronous", &self.synchronous)
+            .field("busy_timeout", &self.busy_timeout)
+            .field("scalar_functions", &self.scalar_functions.len())
+            .finish()
+    }
+}
+
+impl Default for AppFileOptions {
+    /// The defaults used by `AppFile::try_open`: read-write mode, rusqlite's
+    /// own defaults for journal mode and synchronous level, foreign keys
+    /// off, and a 5000ms busy timeout.
+    fn default() -> Self {
+        Self {
+            mode: OpenMode::ReadWrite,
+            wal: false,
+            foreign_keys: false,
+            synchronous: None,
+            busy_timeout: Duration::from_millis(5000),
+            scalar_functions: Vec::new(),
+        }
+    }
+}
+
+impl AppFileOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `OpenMode` used to open the connection.
+    pub fn mode(mut self, mode: OpenMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Enables `PRAGMA journal_mode=WAL`.
+    pub fn wal(mut self) -> Self {
+        self.wal = true;
+        self
+    }
+
+    /// Enables or disables `PRAGMA foreign_keys`.
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = enabled;
+        self
+    }
+
+    /// Sets `PRAGMA synchronous`.
+    pub fn synchronous(mut self, level: Synchronous) -> Self {
+        self.synchronous = Some(level);
+        self
+    }
+
+    /// Sets an explicit busy timeout, instead of the 5000ms default.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// Registers an application-defined scalar SQL function, via
+    /// `Connection::create_scalar_function`, on every connection this
+    /// `AppFile` opens: the initial connection, and with the `pool`
+    /// feature, every pooled connection the manager opens afterwards.
+    pub fn register_scalar_function<F>(mut self, register: F) -> Self
+    where
+        F: Fn(&Connection) -> rusqlite::Result<()> + Send + Sync + 'static,
+    {
+        self.scalar_functions.push(Box::new(register));
+        self
+    }
+}
+
+/// Runs the connection-preparation phase: busy timeout, opted-in pragmas,
+/// and registered scalar functions. Runs outside of any transaction.
+fn prepare(connection: &Connection, options: &AppFileOptions) -> rusqlite::Result<()> {
+    connection.busy_timeout(options.busy_timeout)?;
+
+    if options.wal {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+    }
+
+    if options.foreign_keys {
+        connection.pragma_update(None, "foreign_keys", true)?;
+    }
+
+    if let Some(synchronous) = options.synchronous {
+        connection.pragma_update(None, "synchronous", synchronous.as_pragma_value())?;
+    }
+
+    for register in &options.scalar_functions {
+        register(connection)?;
+    }
+
+    Ok(())
+}
+
+/// The kind of row-level change reported to an `on_change` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+    /// Reported for any action SQLite's hook API doesn't (yet) expose as a
+    /// dedicated variant, e.g. `rusqlite::hooks::Action::UNKNOWN`.
+    Other,
+}
+
+impl From<rusqlite::hooks::Action> for Action {
+    fn from(action: rusqlite::hooks::Action) -> Self {
+        match action {
+            rusqlite::hooks::Action::SQLITE_INSERT => Action::Insert,
+            rusqlite::hooks::Action::SQLITE_UPDATE => Action::Update,
+            rusqlite::hooks::Action::SQLITE_DELETE => Action::Delete,
+            _ => Action::Other,
+        }
+    }
+}
+
+/// A handle that can abort a statement running on the `AppFile` connection
+/// it was obtained from, from another thread (e.g. a UI "cancel" button or a
+/// shutdown signal).
+///
+/// `Send + Sync`: unlike `AppFile` itself, this handle can always be shared
+/// across threads. Interrupting causes the running statement on the
+/// connection to fail with `DbError::Interrupted`.
+pub struct InterruptHandle(rusqlite::InterruptHandle);
+
+impl InterruptHandle {
+    /// Aborts the statement currently running on the associated connection.
+    /// Has no effect if no statement is running.
+    pub fn interrupt(&self) {
+        self.0.interrupt();
+    }
+}
+
+impl fmt::Debug for InterruptHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterruptHandle").finish()
+    }
+}
+
 /// Represents an on-disk file containing the library database.
 ///
-/// `AppFile` is currently not `Sync`, but concurrent writes from multiple
-/// objects are safe, thanks to SQLite concurrency support. Concurrent writes
-/// are blocking, and may timeout.
+/// Without the `pool` feature, `AppFile` holds a single connection: it is
+/// `Send` but not `Sync` or `Clone`, and concurrent writes from multiple
+/// objects are safe, thanks to SQLite concurrency support, but blocking and
+/// subject to the busy timeout (5000ms unless set otherwise through
+/// `AppFileOptions::busy_timeout`).
 ///
-/// Currently, the timeout is set to 5000ms (rusqlite defaults).
+/// With the `pool` feature, `AppFile` holds a pool of connections instead
+/// (see `get`), making it `Clone + Send + Sync` so it can be shared across
+/// threads, e.g. a background indexer and a UI thread, without serializing
+/// on a single connection. `interrupt_handle`, `on_change`, `on_commit`,
+/// `on_rollback` and `clear_change_hooks` are available in this mode too,
+/// but only cover whichever single physical connection `get()` hands back —
+/// see their doc comments on the pooled `impl AppFile`.
 #[derive(Debug)]
+#[cfg_attr(feature = "pool", derive(Clone))]
 pub struct AppFile {
     /// A connection to the sqlite database.
+    #[cfg(not(feature = "pool"))]
     connection: Connection,
+
+    /// A pool of connections to the sqlite database.
+    #[cfg(feature = "pool")]
+    pool: Pool<SqliteConnectionManager>,
 }
 
 /// The version of the file is expected to match the current version and no
@@ -121,43 +482,45 @@ pub struct AppFile {
 /// versionm, any operation may fail or return no data, while writes may fail,
 /// lose data or even corrupt the file.
 ///
-/// After opening, the file version should be checked and upgraded if needed.
+/// After opening in `OpenMode::ReadWrite`, the file is transparently upgraded
+/// to the current version if needed, through the migration chain (see
+/// `upgrade`); in `OpenMode::ReadOnly`, an older file fails to open instead.
+#[cfg(not(feature = "pool"))]
 impl AppFile {
-    /// Tries creating an `AppFile` for the given path.
+    /// Tries creating an `AppFile` for the given path, opened for reading
+    /// and writing.
     ///
-    /// If the file is new or empty, it will be initialized with the current
-    /// format version.
+    /// If the file is new, empty, or on an older schema version, it is
+    /// brought up to the current version through the migration chain before
+    /// this call returns.
     ///
-    /// Returns an error if the file can't be read, initialized (write error)
-    /// or is invalid (not a SQLite database or not with a matching app id).
+    /// Returns an error if the file can't be read, initialized/upgraded
+    /// (write error) or is invalid (not a SQLite database or not with a
+    /// matching app id).
     pub fn try_open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut connection = Connection::open(path.as_ref()).into_open_failed(path.as_ref())?;
-
-        let application_id: i32 = connection
-            .query_row(
-                "SELECT application_id FROM pragma_application_id()",
-                [],
-                |r| r.get(0),
-            )
-            .into_invalid_or_read_failed()?;
+        Self::try_open_with(path, AppFileOptions::default())
+    }
 
-        if application_id != 0 && application_id != SQLITE_APP_ID {
-            return Err(DbError::InvalidFileError);
-        }
+    /// Tries creating an `AppFile` for the given path, using the given
+    /// `AppFileOptions`.
+    ///
+    /// The connection is first prepared (busy timeout, opted-in pragmas,
+    /// registered scalar functions — see `AppFileOptions`), then, depending
+    /// on the `OpenMode` set through `AppFileOptions::mode`:
+    ///
+    /// In `OpenMode::ReadWrite`, behaves like `try_open`: the file is
+    /// created, initialized and upgraded as needed.
+    ///
+    /// In `OpenMode::ReadOnly`, the file is never created or written to: a
+    /// missing file fails with `DbError::OpenFailed`, and a file that would
+    /// need a migration fails with `DbError::UpgradeRequired` instead of
+    /// silently operating on a stale schema.
+    pub fn try_open_with<P: AsRef<Path>>(path: P, options: AppFileOptions) -> Result<Self> {
+        let mut connection = Connection::open_with_flags(path.as_ref(), options.mode.flags())
+            .into_open_failed(path.as_ref())?;
 
-        // If there are no tables, this is a new/uninitialized database.
-        let initialized = connection
-            .prepare("SELECT tbl_name FROM sqlite_schema LIMIT 1")
-            .and_then(|mut s| s.exists([]))
-            .into_invalid_or_read_failed()?;
-
-        if !initialized {
-            let transaction = connection.transaction().into_write_failed()?;
-            transaction
-                .execute_batch(schema().as_ref())
-                .into_write_failed()?;
-            transaction.commit().into_write_failed()?;
-        }
+        prepare(&connection, &options).into_write_failed()?;
+        check_and_prepare(&mut connection, options.mode)?;
 
         Ok(Self { connection })
     }
@@ -165,22 +528,261 @@ impl AppFile {
     /// Returns whether the version of the file matches the current version
     /// (Equal), is older (Less) or newer (Greater).
     pub fn compare_version(&self) -> Result<Ordering> {
-        let user_version: u32 = self
-            .connection
-            .query_row("SELECT user_version FROM pragma_user_version", [], |r| {
-                r.get(0)
-            })
-            .into_read_failed()?;
+        Ok(read_user_version(&self.connection)?.cmp(&SQLITE_USER_VERSION))
+    }
+
+    /// Upgrades the file format to the current version (in place), running
+    /// every pending migration in its own transaction.
+    ///
+    /// `try_open` already runs the migration chain, so this is mostly useful
+    /// to retry an upgrade that failed, or to upgrade a file that was opened
+    /// by other means. It is a no-op if the file is already at the current version.
+    pub fn upgrade(&mut self) -> Result<()> {
+        let user_version = read_user_version(&self.connection)?;
+        if user_version < SQLITE_USER_VERSION {
+            run_migrations(&mut self.connection, user_version)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a consistent, point-in-time copy of this file at `dest`,
+    /// using SQLite's Online Backup API. Unlike a plain file copy, this is
+    /// safe to call while the database is open and being written to (by
+    /// this process or another).
+    pub fn backup_to<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        self.connection
+            .backup(rusqlite::DatabaseName::Main, dest, None)
+            .into_write_failed()
+    }
+
+    /// Backs up the file to `dest`, then upgrades it to the current version
+    /// in place.
+    ///
+    /// The backup runs first; if it fails, nothing is rolled back on the
+    /// live file (since no migration has run yet) and the error is
+    /// returned as-is.
+    pub fn upgrade_with_backup<P: AsRef<Path>>(&mut self, dest: P) -> Result<()> {
+        self.backup_to(dest)?;
+        self.upgrade()
+    }
+
+    /// Returns a handle that can interrupt a statement running on this
+    /// `AppFile`'s connection from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.connection.get_interrupt_handle())
+    }
 
-        Ok(user_version.cmp(&SQLITE_USER_VERSION))
+    /// Registers a callback invoked for every row inserted, updated or
+    /// deleted through this connection — e.g. to invalidate a cache or
+    /// refresh a live UI view without polling.
+    ///
+    /// Replaces any callback previously registered with `on_change`. The
+    /// callback must not call back into this connection (run a query, start
+    /// a transaction, ...): SQLite forbids reentering the connection from
+    /// inside the hook.
+    pub fn on_change<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(Action, &str, i64) + Send + 'static,
+    {
+        self.connection.update_hook(Some(
+            move |action: rusqlite::hooks::Action, _db_name: &str, table: &str, rowid| {
+                callback(action.into(), table, rowid);
+            },
+        ));
     }
 
-    /// Upgrades the file format to the current version (in place).
+    /// Registers a callback invoked right before a transaction commits.
+    /// Replaces any callback previously registered with `on_commit`.
     ///
-    /// Perform a manual copy of the file before the upgrade to avoid data loss.
-    #[allow(clippy::unnecessary_wraps, unused_self, unused_mut)] // for forward compat.
+    /// Returning `true` turns the commit into a rollback (see
+    /// `Connection::commit_hook`). Must not call back into this connection.
+    pub fn on_commit<F>(&mut self, callback: F)
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        self.connection.commit_hook(Some(callback));
+    }
+
+    /// Registers a callback invoked whenever a transaction on this
+    /// connection rolls back. Replaces any callback previously registered
+    /// with `on_rollback`. Must not call back into this connection.
+    pub fn on_rollback<F>(&mut self, callback: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.connection.rollback_hook(Some(callback));
+    }
+
+    /// Clears any change, commit or rollback hook registered through
+    /// `on_change`, `on_commit` or `on_rollback`.
+    pub fn clear_change_hooks(&mut self) {
+        self.connection
+            .update_hook(None::<fn(rusqlite::hooks::Action, &str, &str, i64)>);
+        self.connection.commit_hook(None::<fn() -> bool>);
+        self.connection.rollback_hook(None::<fn()>);
+    }
+}
+
+/// Pooled variant of `AppFile`, built on `r2d2` and `r2d2_sqlite`.
+///
+/// The app-id/user-version check (and migration, if needed) runs once, on a
+/// bootstrap connection, before the pool is built; every other physical
+/// connection the pool opens afterwards only goes through the busy-timeout
+/// and pragma/scalar-function setup in `prepare()`, via the connection
+/// manager's `with_init` hook — it is not re-validated against the app id or
+/// schema version. This is a deliberate simplification: every pooled
+/// connection opens the same on-disk file the bootstrap connection already
+/// validated, and nothing in this module changes the app id or schema
+/// version of a file out from under an open pool.
+#[cfg(feature = "pool")]
+impl AppFile {
+    /// Tries creating a pooled `AppFile` for the given path, opened for
+    /// reading and writing. See the non-pooled `try_open` for the
+    /// initialization/upgrade semantics; they are identical here.
+    pub fn try_open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::try_open_with(path, AppFileOptions::default())
+    }
+
+    /// Tries creating a pooled `AppFile` for the given path, using the given
+    /// `AppFileOptions`. See the non-pooled `try_open_with` for the
+    /// preparation/initialization/upgrade semantics; they are identical
+    /// here, except that the preparation phase is re-applied to every
+    /// physical connection the pool opens afterwards, via the connection
+    /// manager's `with_init` hook.
+    pub fn try_open_with<P: AsRef<Path>>(path: P, options: AppFileOptions) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mode = options.mode;
+
+        // Run the one-time init/upgrade check on a single connection before
+        // handing out a pool built from the same path.
+        let mut connection =
+            Connection::open_with_flags(&path, mode.flags()).into_open_failed(path.as_path())?;
+        prepare(&connection, &options).into_write_failed()?;
+        check_and_prepare(&mut connection, mode)?;
+        drop(connection);
+
+        let manager = SqliteConnectionManager::file(&path)
+            .with_flags(mode.flags())
+            .with_init(move |connection| prepare(connection, &options));
+        let pool = Pool::new(manager).map_err(|cause| DbError::PoolFailed { cause })?;
+
+        Ok(Self { pool })
+    }
+
+    /// Checks out a connection from the pool.
+    pub fn get(&self) -> Result<r2d2::PooledConnection<SqliteConnectionManager>> {
+        self.pool
+            .get()
+            .map_err(|cause| DbError::PoolFailed { cause })
+    }
+
+    /// Returns whether the version of the file matches the current version
+    /// (Equal), is older (Less) or newer (Greater).
+    pub fn compare_version(&self) -> Result<Ordering> {
+        let connection = self.get()?;
+        Ok(read_user_version(&connection)?.cmp(&SQLITE_USER_VERSION))
+    }
+
+    /// Upgrades the file format to the current version (in place), running
+    /// every pending migration in its own transaction.
     pub fn upgrade(&mut self) -> Result<()> {
-        // Currently a no-op since there is only one version.
+        let mut connection = self.get()?;
+        let user_version = read_user_version(&connection)?;
+        if user_version < SQLITE_USER_VERSION {
+            run_migrations(&mut connection, user_version)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a consistent, point-in-time copy of this file at `dest`,
+    /// using SQLite's Online Backup API. Unlike a plain file copy, this is
+    /// safe to call while the database is open and being written to (by
+    /// this process or another).
+    pub fn backup_to<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        self.get()?
+            .backup(rusqlite::DatabaseName::Main, dest, None)
+            .into_write_failed()
+    }
+
+    /// Backs up the file to `dest`, then upgrades it to the current version
+    /// in place.
+    ///
+    /// The backup runs first; if it fails, nothing is rolled back on the
+    /// live file (since no migration has run yet) and the error is
+    /// returned as-is.
+    pub fn upgrade_with_backup<P: AsRef<Path>>(&mut self, dest: P) -> Result<()> {
+        self.backup_to(dest)?;
+        self.upgrade()
+    }
+
+    /// Returns a handle that can interrupt a statement running on a
+    /// connection checked out from the pool.
+    ///
+    /// Unlike the non-pooled `interrupt_handle`, this only covers the one
+    /// physical connection `get()` happens to hand back, not every
+    /// connection the pool may open: interrupting another in-flight
+    /// statement on a different checked-out connection isn't possible
+    /// through this handle.
+    pub fn interrupt_handle(&self) -> Result<InterruptHandle> {
+        Ok(InterruptHandle(self.get()?.get_interrupt_handle()))
+    }
+
+    /// Registers a callback invoked for every row inserted, updated or
+    /// deleted through the connection `get()` happens to hand back.
+    ///
+    /// Since the pool may open more than one physical connection, this only
+    /// observes changes made through that one connection, not every
+    /// connection the pool hands out — e.g. a background indexer must reuse
+    /// the same `get()`'d connection for its writes for a UI thread's
+    /// `on_change` callback (registered on that same connection) to see
+    /// them. The callback must not call back into the connection.
+    pub fn on_change<F>(&self, mut callback: F) -> Result<()>
+    where
+        F: FnMut(Action, &str, i64) + Send + 'static,
+    {
+        self.get()?.update_hook(Some(
+            move |action: rusqlite::hooks::Action, _db_name: &str, table: &str, rowid| {
+                callback(action.into(), table, rowid);
+            },
+        ));
+        Ok(())
+    }
+
+    /// Registers a callback invoked right before a transaction commits on
+    /// the connection `get()` happens to hand back. Same single-connection
+    /// caveat as `on_change`.
+    ///
+    /// Returning `true` turns the commit into a rollback (see
+    /// `Connection::commit_hook`). Must not call back into the connection.
+    pub fn on_commit<F>(&self, callback: F) -> Result<()>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        self.get()?.commit_hook(Some(callback));
+        Ok(())
+    }
+
+    /// Registers a callback invoked whenever a transaction rolls back on the
+    /// connection `get()` happens to hand back. Same single-connection
+    /// caveat as `on_change`. Must not call back into the connection.
+    pub fn on_rollback<F>(&self, callback: F) -> Result<()>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.get()?.rollback_hook(Some(callback));
+        Ok(())
+    }
+
+    /// Clears any change, commit or rollback hook registered through
+    /// `on_change`, `on_commit` or `on_rollback` on the connection `get()`
+    /// happens to hand back.
+    pub fn clear_change_hooks(&self) -> Result<()> {
+        let connection = self.get()?;
+        connection.update_hook(None::<fn(rusqlite::hooks::Action, &str, &str, i64)>);
+        connection.commit_hook(None::<fn() -> bool>);
+        connection.rollback_hook(None::<fn()>);
         Ok(())
     }
 }
@@ -191,7 +793,7 @@ mod tests {
 
     use crate::db::SQLITE_USER_VERSION;
 
-    use super::{AppFile, DbError};
+    use super::{Action, AppFile, AppFileOptions, DbError, OpenMode};
     use googletest::prelude::*;
     use rusqlite::Connection;
 
@@ -262,7 +864,7 @@ mod tests {
     }
 
     #[test]
-    fn test_compare_version() -> Result<()> {
+    fn test_compare_version_newer_file() -> Result<()> {
         let file = tempfile::NamedTempFile::new()?;
         // Initializes the file.
         AppFile::try_open(file.path())?;
@@ -271,28 +873,56 @@ mod tests {
             let con = rusqlite::Connection::open(file.path())?;
             con.execute(
                 // we can't use a placeholder value (query param) in the PRAGMA statement.
-                format!("PRAGMA user_version = {};", SQLITE_USER_VERSION - 1).as_str(),
+                format!("PRAGMA user_version = {};", SQLITE_USER_VERSION + 1).as_str(),
                 [],
             )?;
         }
         verify_eq!(
             AppFile::try_open(file.path())?.compare_version()?,
-            Ordering::Less
-        )?;
+            Ordering::Greater
+        )
+    }
+
+    #[test]
+    fn test_open_upgrades_older_file() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        // Initializes the file.
+        AppFile::try_open(file.path())?;
 
         {
             let con = rusqlite::Connection::open(file.path())?;
-            con.execute(
-                format!("PRAGMA user_version = {};", SQLITE_USER_VERSION + 1).as_str(),
-                [],
-            )?;
+            con.execute("PRAGMA user_version = 0;", [])?;
         }
+
+        // Re-opening the file runs the migration chain again and brings it
+        // back to the current version.
         verify_eq!(
             AppFile::try_open(file.path())?.compare_version()?,
-            Ordering::Greater
+            Ordering::Equal
+        )
+    }
+
+    #[test]
+    fn test_open_read_only_fails_on_fresh_file() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+
+        verify_that!(
+            AppFile::try_open_with(tmpfile.path(), AppFileOptions::new().mode(OpenMode::ReadOnly)),
+            err(pat!(DbError::UpgradeRequired))
         )
     }
 
+    #[test]
+    fn test_open_read_only_succeeds_on_current_version_file() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        // Initialize the file first.
+        AppFile::try_open(tmpfile.path())?;
+
+        let appfile =
+            AppFile::try_open_with(tmpfile.path(), AppFileOptions::new().mode(OpenMode::ReadOnly))?;
+        verify_eq!(appfile.compare_version()?, Ordering::Equal)
+    }
+
     #[test]
     fn test_upgrade() -> Result<()> {
         let tmpfile = tempfile::NamedTempFile::new()?;
@@ -302,4 +932,237 @@ mod tests {
 
         Ok(())
     }
+
+    // Reaches into `AppFile::connection` directly, which only exists without
+    // the `pool` feature (the pooled variant has no single connection to
+    // inspect).
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_open_with_wal_and_foreign_keys() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let options = AppFileOptions::new().wal().foreign_keys(true);
+        let appfile = AppFile::try_open_with(tmpfile.path(), options)?;
+
+        let journal_mode: String =
+            appfile
+                .connection
+                .query_row("PRAGMA journal_mode", [], |r| r.get(0))?;
+        let foreign_keys: bool =
+            appfile
+                .connection
+                .query_row("PRAGMA foreign_keys", [], |r| r.get(0))?;
+
+        verify_eq!(journal_mode, "wal")?;
+        verify_that!(foreign_keys, eq(true))
+    }
+
+    // Same restriction as `test_open_with_wal_and_foreign_keys`.
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_open_with_registered_scalar_function() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let options = AppFileOptions::new().register_scalar_function(|connection| {
+            connection.create_scalar_function(
+                "answer_to_everything",
+                0,
+                rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
+                |_| Ok(42),
+            )
+        });
+        let appfile = AppFile::try_open_with(tmpfile.path(), options)?;
+
+        let answer: i64 =
+            appfile
+                .connection
+                .query_row("SELECT answer_to_everything()", [], |r| r.get(0))?;
+
+        verify_eq!(answer, 42)
+    }
+
+    #[test]
+    fn test_backup_to() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let appfile = AppFile::try_open(tmpfile.path())?;
+
+        let dest = tempfile::NamedTempFile::new()?;
+        appfile.backup_to(dest.path())?;
+
+        let backup = AppFile::try_open(dest.path())?;
+        verify_eq!(backup.compare_version()?, Ordering::Equal)
+    }
+
+    #[test]
+    fn test_upgrade_with_backup() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let mut appfile = AppFile::try_open(tmpfile.path())?;
+
+        {
+            let con = rusqlite::Connection::open(tmpfile.path())?;
+            con.execute("PRAGMA user_version = 0;", [])?;
+        }
+
+        let dest = tempfile::NamedTempFile::new()?;
+        appfile.upgrade_with_backup(dest.path())?;
+
+        // The backup was taken before the upgrade, so it's still at the old
+        // version. It is opened read-only so that opening it doesn't upgrade
+        // it.
+        let backup =
+            AppFile::try_open_with(dest.path(), AppFileOptions::new().mode(OpenMode::ReadOnly));
+        verify_that!(backup, err(pat!(DbError::UpgradeRequired)))
+    }
+
+    // Only the non-pool `AppFile` exposes a single connection to interrupt.
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_interrupt_long_running_query() -> Result<()> {
+        use super::IntoResult;
+
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let appfile = AppFile::try_open(tmpfile.path())?;
+        let handle = appfile.interrupt_handle();
+
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            handle.interrupt();
+        });
+
+        let result = appfile
+            .connection
+            .execute_batch(
+                "WITH RECURSIVE spin(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM spin) \
+                 SELECT count(*) FROM spin;",
+            )
+            .into_read_failed();
+
+        verify_that!(result, err(pat!(DbError::Interrupted)))
+    }
+
+    // Writes through `AppFile::connection`, which only exists without the
+    // `pool` feature.
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_on_change_reports_inserts() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let mut appfile = AppFile::try_open(tmpfile.path())?;
+
+        let changes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let changes_in_hook = changes.clone();
+        appfile.on_change(move |action, table, _rowid| {
+            changes_in_hook
+                .lock()
+                .unwrap()
+                .push((action, table.to_string()));
+        });
+
+        appfile.connection.execute(
+            "INSERT INTO app_metadata (version, upgraded_from) VALUES ('9.9.9', NULL)",
+            [],
+        )?;
+
+        let got = changes.lock().unwrap().clone();
+        verify_eq!(
+            got,
+            vec![(Action::Insert, "app_metadata".to_string())]
+        )
+    }
+
+    // Same restriction as `test_on_change_reports_inserts`.
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_on_commit_veto_triggers_rollback_hook() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let mut appfile = AppFile::try_open(tmpfile.path())?;
+
+        let rolled_back = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let rolled_back_in_hook = rolled_back.clone();
+        appfile.on_rollback(move || {
+            rolled_back_in_hook.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+        appfile.on_commit(|| true);
+
+        let transaction = appfile.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO app_metadata (version, upgraded_from) VALUES ('9.9.9', NULL)",
+            [],
+        )?;
+        verify_that!(transaction.commit(), err(anything()))?;
+
+        verify_that!(
+            rolled_back.load(std::sync::atomic::Ordering::SeqCst),
+            eq(true)
+        )
+    }
+
+    // Same restriction as `test_on_change_reports_inserts`.
+    #[cfg(not(feature = "pool"))]
+    #[test]
+    fn test_clear_change_hooks() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let mut appfile = AppFile::try_open(tmpfile.path())?;
+
+        let called = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let called_in_hook = called.clone();
+        appfile.on_change(move |_, _, _| {
+            called_in_hook.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+        appfile.clear_change_hooks();
+
+        appfile.connection.execute(
+            "INSERT INTO app_metadata (version, upgraded_from) VALUES ('9.9.9', NULL)",
+            [],
+        )?;
+
+        verify_that!(called.load(std::sync::atomic::Ordering::SeqCst), eq(false))
+    }
+
+    #[cfg(feature = "pool")]
+    #[test]
+    fn test_pool_open_and_get() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let appfile = AppFile::try_open(tmpfile.path())?;
+
+        verify_eq!(appfile.compare_version()?, Ordering::Equal)?;
+        appfile.get()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "pool")]
+    #[test]
+    fn test_pool_shared_across_threads() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let appfile = AppFile::try_open(tmpfile.path())?;
+
+        let other = appfile.clone();
+        std::thread::spawn(move || other.get().expect("failed to get pooled connection"))
+            .join()
+            .expect("thread panicked");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "pool")]
+    #[test]
+    fn test_pool_on_change_observes_writes_on_same_connection() -> Result<()> {
+        let tmpfile = tempfile::NamedTempFile::new()?;
+        let appfile = AppFile::try_open(tmpfile.path())?;
+
+        let changes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let changes_in_hook = changes.clone();
+        appfile.on_change(move |action, table, _rowid| {
+            changes_in_hook
+                .lock()
+                .unwrap()
+                .push((action, table.to_string()));
+        })?;
+
+        appfile.get()?.execute(
+            "INSERT INTO app_metadata (version, upgraded_from) VALUES ('9.9.9', NULL)",
+            [],
+        )?;
+
+        let got = changes.lock().unwrap().clone();
+        verify_eq!(got, vec![(Action::Insert, "app_metadata".to_string())])
+    }
 }